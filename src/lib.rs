@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
 use std::collections::hash_map::RandomState;
+use std::collections::TryReserveError;
 use std::hash::{BuildHasher, Hash};
 use std::iter::Chain;
 use xlru_cache::LruCache;
@@ -18,6 +19,100 @@ where
     inserted: u64,
     evicted: u64,
     removed: u64,
+    hits: u64,
+    misses: u64,
+    admission: Option<FrequencySketch<S>>,
+}
+
+/// Selects an opt-in admission policy for `with_admission`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionPolicy {
+    /// TinyLFU: a brand-new key is only admitted when the cache is full if
+    /// it is estimated at least as frequently seen as the entry it'd evict.
+    TinyLfu,
+}
+
+/// The outcome of `insert`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// `key` was already live (its value was replaced) or was remembered in
+    /// a ghost list; either way it is now stored in the frequent set.
+    Updated,
+    /// `key` was new and has been stored.
+    Inserted,
+    /// `key` was new but the admission filter dropped it without storing it.
+    Rejected,
+}
+
+/// A count-min sketch of saturating 4-bit counters, backing `AdmissionPolicy::TinyLfu`.
+struct FrequencySketch<S> {
+    width: usize,
+    counters: Vec<u8>,
+    hash_builder: S,
+    increments: usize,
+    sample_size: usize,
+}
+
+const FREQUENCY_SKETCH_DEPTH: usize = 4;
+const FREQUENCY_SKETCH_COUNTER_MAX: u8 = 15;
+
+impl<S> FrequencySketch<S>
+where
+    S: BuildHasher,
+{
+    fn new(capacity: usize, hash_builder: S) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        FrequencySketch {
+            width,
+            counters: vec![0u8; FREQUENCY_SKETCH_DEPTH * width],
+            hash_builder,
+            increments: 0,
+            sample_size: 10 * capacity.max(1),
+        }
+    }
+
+    fn slot<Q: ?Sized + Hash>(&self, row: usize, key: &Q) -> usize {
+        let row_hash = self.hash_builder.hash_one((row, key));
+        row * self.width + (row_hash as usize % self.width)
+    }
+
+    fn estimate<Q: ?Sized + Hash>(&self, key: &Q) -> u8 {
+        (0..FREQUENCY_SKETCH_DEPTH)
+            .map(|row| self.counters[self.slot(row, key)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn increment<Q: ?Sized + Hash>(&mut self, key: &Q) {
+        for row in 0..FREQUENCY_SKETCH_DEPTH {
+            let slot = self.slot(row, key);
+            if self.counters[slot] < FREQUENCY_SKETCH_COUNTER_MAX {
+                self.counters[slot] += 1;
+            }
+        }
+        self.increments += 1;
+        if self.increments >= self.sample_size {
+            for counter in &mut self.counters {
+                *counter /= 2;
+            }
+            self.increments = 0;
+        }
+    }
+}
+
+/// Error returned by `try_with_capacity`.
+#[derive(Debug)]
+pub enum TryWithCapacityError {
+    /// `capacity` was zero.
+    InvalidCapacity(&'static str),
+    /// Reserving the backing storage failed.
+    TryReserve(TryReserveError),
+}
+
+impl From<TryReserveError> for TryWithCapacityError {
+    fn from(err: TryReserveError) -> Self {
+        TryWithCapacityError::TryReserve(err)
+    }
 }
 
 /// An iterator over all items in the cache. Iterates over frequently-used items
@@ -47,9 +142,55 @@ where
             inserted: 0,
             evicted: 0,
             removed: 0,
+            hits: 0,
+            misses: 0,
+            admission: None,
         };
         Ok(cache)
     }
+
+    /// Creates an empty cache like `new`, but with an opt-in admission
+    /// policy that filters which newly-seen keys get admitted once the
+    /// cache is full.
+    pub fn with_admission(capacity: usize, policy: AdmissionPolicy) -> Result<Self, &'static str> {
+        let mut cache = Self::new(capacity)?;
+        cache.admission = Some(match policy {
+            AdmissionPolicy::TinyLfu => FrequencySketch::new(capacity, RandomState::new()),
+        });
+        Ok(cache)
+    }
+
+    /// Like `new`, but preallocates the backing storage for all four
+    /// sub-lists up front and surfaces a failure as `Err` instead of aborting.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryWithCapacityError> {
+        if capacity == 0 {
+            return Err(TryWithCapacityError::InvalidCapacity(
+                "Cache length cannot be zero",
+            ));
+        }
+        let mut recent_set = LruCache::new(capacity);
+        recent_set.try_reserve(capacity)?;
+        let mut recent_evicted = LruCache::new(capacity);
+        recent_evicted.try_reserve(capacity)?;
+        let mut frequent_set = LruCache::new(capacity);
+        frequent_set.try_reserve(capacity)?;
+        let mut frequent_evicted = LruCache::new(capacity);
+        frequent_evicted.try_reserve(capacity)?;
+        Ok(ArcCache {
+            recent_set,
+            recent_evicted,
+            frequent_set,
+            frequent_evicted,
+            capacity,
+            p: 0,
+            inserted: 0,
+            evicted: 0,
+            removed: 0,
+            hits: 0,
+            misses: 0,
+            admission: None,
+        })
+    }
 }
 
 impl<K, V, S> ArcCache<K, V, S>
@@ -75,27 +216,80 @@ where
             inserted: 0,
             evicted: 0,
             removed: 0,
+            hits: 0,
+            misses: 0,
+            admission: None,
         };
         Ok(cache)
     }
 
+    /// Changes the cache's capacity, rebalancing the recent/frequent sets and
+    /// their ghost lists so that they fit within the new limit.
+    ///
+    /// Shrinking evicts least-recently-used entries (and trims the ghost
+    /// lists) until the cache fits; growing simply raises the limits without
+    /// touching the contents.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "Cache length cannot be zero");
+        self.p = self.p.clamp(0, capacity);
+        while self.recent_len() + self.frequent_len() > capacity {
+            self.replace(false);
+        }
+        self.recent_set.set_capacity(capacity);
+        self.frequent_set.set_capacity(capacity);
+        self.recent_evicted.set_capacity(capacity);
+        self.frequent_evicted.set_capacity(capacity);
+        while self.recent_evicted.len() > capacity {
+            self.recent_evicted.remove_lru();
+        }
+        while self.frequent_evicted.len() > capacity {
+            self.frequent_evicted.remove_lru();
+        }
+        self.capacity = capacity;
+    }
+
     pub fn contains_key<Q: ?Sized>(&mut self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(key);
+        }
         self.frequent_set.contains_key(key) || self.recent_set.contains_key(key)
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> bool {
+    /// Like `insert`, but reserves space in the sub-list the key would land
+    /// in before inserting, surfacing an allocation failure as `Err` instead
+    /// of aborting. Updating a key already in `frequent_set` needs no
+    /// reservation, since it doesn't grow either set.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<InsertOutcome, TryReserveError> {
+        if self.frequent_set.contains_key(&key) {
+            // Replacing the value in place; neither set grows.
+        } else if self.recent_set.contains_key(&key)
+            || self.frequent_evicted.contains_key(&key)
+            || self.recent_evicted.contains_key(&key)
+        {
+            self.frequent_set.try_reserve(1)?;
+        } else {
+            self.recent_set.try_reserve(1)?;
+        }
+        Ok(self.insert(key, value))
+    }
+
+    /// Inserts `key`/`value`. See `InsertOutcome`.
+    pub fn insert(&mut self, key: K, value: V) -> InsertOutcome {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(&key);
+        }
         if self.frequent_set.contains_key(&key) {
             self.frequent_set.insert(key, value);
-            return true;
+            return InsertOutcome::Updated;
         }
         if self.recent_set.contains_key(&key) {
             self.recent_set.remove(&key);
             self.frequent_set.insert(key, value);
-            return true;
+            return InsertOutcome::Updated;
         }
         if self.frequent_evicted.contains_key(&key) {
             let recent_evicted_len = self.recent_evicted.len();
@@ -115,7 +309,7 @@ where
             }
             self.frequent_evicted.remove(&key);
             self.frequent_set.insert(key, value);
-            return true;
+            return InsertOutcome::Updated;
         }
         if self.recent_evicted.contains_key(&key) {
             let recent_evicted_len = self.recent_evicted.len();
@@ -135,9 +329,16 @@ where
             }
             self.recent_evicted.remove(&key);
             self.frequent_set.insert(key, value);
-            return true;
+            return InsertOutcome::Updated;
         }
         if self.recent_set.len() + self.frequent_set.len() >= self.capacity {
+            if let Some(sketch) = &self.admission {
+                if let Some(victim) = self.peek_victim(false) {
+                    if sketch.estimate(victim) > sketch.estimate(&key) {
+                        return InsertOutcome::Rejected;
+                    }
+                }
+            }
             self.replace(false);
         }
         if self.recent_evicted.len() > self.capacity - self.p {
@@ -150,31 +351,83 @@ where
         }
         self.recent_set.insert(key, value);
         self.inserted += 1;
-        false
+        InsertOutcome::Inserted
     }
 
-    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V>
+    pub fn peek_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&mut V>
     where
-        K: Clone + Hash + Eq,
+        K: Borrow<Q>,
     {
-        if let Some(entry) = self.frequent_set.peek_mut(key) {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(key);
+        }
+        let entry = if let Some(entry) = self.frequent_set.peek_mut(key) {
             Some(entry)
         } else {
             self.recent_set.peek_mut(key)
+        };
+        if entry.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        entry
+    }
+
+    pub fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(key);
+        }
+        if let Some((owned_key, value)) = self.recent_set.remove_entry(key) {
+            self.frequent_set.insert(owned_key, value);
         }
+        let entry = self.frequent_set.get_mut(key);
+        if entry.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        entry
     }
 
-    pub fn get_mut(&mut self, key: &K) -> Option<&mut V>
+    /// Like `get_mut`, but returns a shared reference; use `peek` instead if
+    /// you don't want a T1 hit promoted into `frequent_set`.
+    pub fn get<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&V>
     where
-        K: Clone + Hash + Eq,
+        K: Borrow<Q>,
     {
-        if let Some(value) = self.recent_set.remove(key) {
-            self.frequent_set.insert((*key).clone(), value);
+        if let Some(sketch) = self.admission.as_mut() {
+            sketch.increment(key);
         }
-        self.frequent_set.get_mut(key)
+        if let Some((owned_key, value)) = self.recent_set.remove_entry(key) {
+            self.frequent_set.insert(owned_key, value);
+        }
+        let entry = self.frequent_set.get_mut(key).map(|value| &*value);
+        if entry.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        entry
     }
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
+    /// Non-mutating, non-promoting counterpart to `peek_mut`.
+    pub fn peek<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.frequent_set
+            .peek(key)
+            .or_else(|| self.recent_set.peek(key))
+    }
+
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
         let removed_frequent = self.frequent_set.remove(key);
         let removed_recent = self.recent_set.remove(key);
 
@@ -190,6 +443,64 @@ where
         }
     }
 
+    /// Removes every entry for which `f` returns `false`, cleaning up the
+    /// corresponding ghost-list entries just like `remove` does.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut kept = Vec::with_capacity(self.frequent_set.len());
+        while let Some((key, mut value)) = self.frequent_set.remove_lru() {
+            if f(&key, &mut value) {
+                kept.push((key, value));
+            } else {
+                self.frequent_evicted.remove(&key);
+                self.recent_evicted.remove(&key);
+                self.removed += 1;
+            }
+        }
+        for (key, value) in kept {
+            self.frequent_set.insert(key, value);
+        }
+
+        let mut kept = Vec::with_capacity(self.recent_set.len());
+        while let Some((key, mut value)) = self.recent_set.remove_lru() {
+            if f(&key, &mut value) {
+                kept.push((key, value));
+            } else {
+                self.frequent_evicted.remove(&key);
+                self.recent_evicted.remove(&key);
+                self.removed += 1;
+            }
+        }
+        for (key, value) in kept {
+            self.recent_set.insert(key, value);
+        }
+    }
+
+    /// Removes and returns every live entry, frequently-used items first,
+    /// leaving the cache (including its ghost lists) empty.
+    pub fn drain(&mut self) -> Drain<'_, K, V, S> {
+        self.recent_evicted.clear();
+        self.frequent_evicted.clear();
+        Drain {
+            cache: self,
+            draining_frequent: true,
+        }
+    }
+
+    fn peek_victim(&self, frequent_evicted_contains_key: bool) -> Option<&K> {
+        let recent_set_len = self.recent_set.len();
+        if recent_set_len > 0
+            && (recent_set_len > self.p
+                || (recent_set_len == self.p && frequent_evicted_contains_key))
+        {
+            self.recent_set.peek_lru().map(|(k, _)| k)
+        } else {
+            self.frequent_set.peek_lru().map(|(k, _)| k)
+        }
+    }
+
     fn replace(&mut self, frequent_evicted_contains_key: bool) {
         let recent_set_len = self.recent_set.len();
         if recent_set_len > 0
@@ -238,6 +549,25 @@ where
     pub fn removed(&self) -> u64 {
         self.removed
     }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the ratio of hits to total lookups, or `0.0` if no lookups
+    /// have occurred yet.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
 }
 
 impl<'a, K, V, S> IntoIterator for &'a ArcCache<K, V, S>
@@ -264,6 +594,201 @@ where
     }
 }
 
+/// Draining iterator for `ArcCache::drain`. Yields frequently-used items
+/// first, then recently-used ones; dropping it before exhaustion still
+/// removes the remaining entries, matching `Vec`/`HashMap`'s `Drain`.
+pub struct Drain<'a, K, V, S = RandomState>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    cache: &'a mut ArcCache<K, V, S>,
+    draining_frequent: bool,
+}
+
+impl<'a, K, V, S> Iterator for Drain<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.draining_frequent {
+            if let Some(entry) = self.cache.frequent_set.remove_lru() {
+                return Some(entry);
+            }
+            self.draining_frequent = false;
+        }
+        self.cache.recent_set.remove_lru()
+    }
+}
+
+impl<'a, K, V, S> Drop for Drain<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        for _ in self.by_ref() {}
+    }
+}
+
+#[test]
+fn test_set_capacity_shrinks_and_grows() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(4).unwrap();
+    arc.insert(1, 1);
+    arc.insert(2, 2);
+    arc.insert(3, 3);
+    arc.insert(4, 4);
+
+    arc.set_capacity(2);
+    assert_eq!(arc.len(), 2);
+    assert!(arc.contains_key(&3));
+    assert!(arc.contains_key(&4));
+
+    arc.set_capacity(4);
+    arc.insert(5, 5);
+    arc.insert(6, 6);
+    assert_eq!(arc.len(), 4);
+}
+
+#[test]
+#[should_panic(expected = "Cache length cannot be zero")]
+fn test_set_capacity_rejects_zero() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(4).unwrap();
+    arc.set_capacity(0);
+}
+
+#[test]
+fn test_hit_ratio() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(2).unwrap();
+    arc.insert(1, 1);
+    assert_eq!(arc.hit_ratio(), 0.0);
+
+    arc.get_mut(&1);
+    arc.get_mut(&2);
+    assert_eq!(arc.hits(), 1);
+    assert_eq!(arc.misses(), 1);
+    assert_eq!(arc.hit_ratio(), 0.5);
+}
+
+#[test]
+fn test_borrow_lookups_accept_str_for_string_key() {
+    let mut arc: ArcCache<String, i32> = ArcCache::new(2).unwrap();
+    arc.insert("testkey".to_string(), 1);
+
+    assert_eq!(arc.get_mut("testkey"), Some(&mut 1));
+    assert_eq!(arc.peek_mut("testkey"), Some(&mut 1));
+    assert_eq!(arc.remove("testkey"), Some(1));
+    assert!(!arc.contains_key("testkey"));
+}
+
+#[test]
+fn test_get_promotes_without_clone() {
+    struct NotClone(i32);
+
+    let mut arc: ArcCache<i32, NotClone> = ArcCache::new(2).unwrap();
+    arc.insert(1, NotClone(10));
+    arc.insert(2, NotClone(20));
+
+    assert_eq!(arc.get(&1).map(|v| v.0), Some(10));
+    assert_eq!(arc.frequent_len(), 1);
+    assert_eq!(arc.peek(&2).map(|v| v.0), Some(20));
+    assert_eq!(arc.recent_len(), 1);
+}
+
+#[test]
+fn test_retain() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(4).unwrap();
+    arc.insert(1, 1);
+    arc.insert(2, 2);
+    arc.insert(3, 3);
+
+    arc.retain(|_, v| *v != 2);
+
+    assert!(arc.contains_key(&1));
+    assert!(!arc.contains_key(&2));
+    assert!(arc.contains_key(&3));
+    assert_eq!(arc.len(), 2);
+    assert_eq!(arc.removed(), 1);
+}
+
+#[test]
+fn test_drain() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(4).unwrap();
+    arc.insert(1, 1);
+    arc.insert(2, 2);
+    arc.insert(3, 3);
+    arc.insert(4, 4);
+
+    {
+        let mut drain = arc.drain();
+        assert!(drain.next().is_some());
+        assert!(drain.next().is_some());
+    }
+
+    assert_eq!(arc.len(), 0);
+    assert!(arc.is_empty());
+}
+
+#[test]
+fn test_insert_outcome_distinguishes_update_from_insert() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::new(2).unwrap();
+    assert_eq!(arc.insert(1, 1), InsertOutcome::Inserted);
+    assert_eq!(arc.insert(1, 2), InsertOutcome::Updated);
+}
+
+#[test]
+fn test_with_admission_rejects_cold_newcomer() {
+    let mut arc: ArcCache<i32, i32> = ArcCache::with_admission(1, AdmissionPolicy::TinyLfu).unwrap();
+    arc.insert(1, 1);
+    for _ in 0..10 {
+        arc.contains_key(&1);
+    }
+
+    assert_eq!(arc.insert(2, 2), InsertOutcome::Rejected);
+    assert!(arc.contains_key(&1));
+    assert!(!arc.contains_key(&2));
+}
+
+#[test]
+fn test_try_with_capacity() {
+    let arc: ArcCache<i32, i32> = ArcCache::try_with_capacity(2).unwrap();
+    assert_eq!(arc.len(), 0);
+
+    match ArcCache::<i32, i32>::try_with_capacity(0) {
+        Err(TryWithCapacityError::InvalidCapacity(_)) => {}
+        Err(other) => panic!("expected InvalidCapacity error, got {other:?}"),
+        Ok(_) => panic!("expected an error for zero capacity"),
+    }
+}
+
+#[test]
+fn test_try_insert() {
+    let mut arc: ArcCache<&str, &str> = ArcCache::new(2).unwrap();
+    assert_eq!(
+        arc.try_insert("testkey", "testvalue"),
+        Ok(InsertOutcome::Inserted)
+    );
+    assert!(arc.contains_key(&"testkey"));
+}
+
+#[test]
+fn test_try_insert_updates_frequent_entry_without_reserving() {
+    let mut arc: ArcCache<&str, &str> = ArcCache::new(2).unwrap();
+    arc.insert("testkey", "testvalue");
+    // Looking it up after the insert promotes it into `frequent_set`.
+    arc.get_mut("testkey");
+    assert_eq!(arc.frequent_len(), 1);
+
+    assert_eq!(
+        arc.try_insert("testkey", "updated"),
+        Ok(InsertOutcome::Updated)
+    );
+    assert_eq!(arc.peek("testkey"), Some(&"updated"));
+}
+
 #[test]
 fn test_arc() {
     let mut arc: ArcCache<&str, &str> = ArcCache::new(2).unwrap();